@@ -0,0 +1,604 @@
+use crate::{Error, ErrorKind, Result, StorageFile};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const TAG_SIZE: u64 = 128;
+const EXT_SIZE: u64 = 128;
+const ENHANCED_SIZE: u64 = 227;
+
+const GENRES: &[&str] = &[
+    "Blues",
+    "Classic Rock",
+    "Country",
+    "Dance",
+    "Disco",
+    "Funk",
+    "Grunge",
+    "Hip-Hop",
+    "Jazz",
+    "Metal",
+    "New Age",
+    "Oldies",
+    "Other",
+    "Pop",
+    "R&B",
+    "Rap",
+    "Reggae",
+    "Rock",
+    "Techno",
+    "Industrial",
+    "Alternative",
+    "Ska",
+    "Death Metal",
+    "Pranks",
+    "Soundtrack",
+    "Euro-Techno",
+    "Ambient",
+    "Trip-Hop",
+    "Vocal",
+    "Jazz+Funk",
+    "Fusion",
+    "Trance",
+    "Classical",
+    "Instrumental",
+    "Acid",
+    "House",
+    "Game",
+    "Sound Clip",
+    "Gospel",
+    "Noise",
+    "Alternative Rock",
+    "Bass",
+    "Soul",
+    "Punk",
+    "Space",
+    "Meditative",
+    "Instrumental Pop",
+    "Instrumental Rock",
+    "Ethnic",
+    "Gothic",
+    "Darkwave",
+    "Techno-Industrial",
+    "Electronic",
+    "Pop-Folk",
+    "Eurodance",
+    "Dream",
+    "Southern Rock",
+    "Comedy",
+    "Cult",
+    "Gangsta",
+    "Top 40",
+    "Christian Rap",
+    "Pop/Funk",
+    "Jungle",
+    "Native American",
+    "Cabaret",
+    "New Wave",
+    "Psychedelic",
+    "Rave",
+    "Showtunes",
+    "Trailer",
+    "Lo-Fi",
+    "Tribal",
+    "Acid Punk",
+    "Acid Jazz",
+    "Polka",
+    "Retro",
+    "Musical",
+    "Rock & Roll",
+    "Hard Rock",
+];
+
+/// Translates an ID3v1 genre index into its name, per the original 80-entry genre table.
+pub fn genre_name(index: u8) -> Option<&'static str> {
+    GENRES.get(index as usize).copied()
+}
+
+/// Translates a genre name into its ID3v1 genre index, if it appears in the original 80-entry
+/// genre table. The match is case-insensitive.
+pub fn genre_index(name: &str) -> Option<u8> {
+    GENRES
+        .iter()
+        .position(|genre| genre.eq_ignore_ascii_case(name))
+        .map(|i| i as u8)
+}
+
+/// Describes which on-disk layout of an ID3v1 tag, if any, was found at the end of a file.
+#[derive(Copy, Clone, Debug, Hash, Ord, PartialOrd, Eq, PartialEq)]
+pub enum Layout {
+    /// No ID3v1 tag present.
+    None,
+    /// A bare 128-byte ID3v1/ID3v1.1 `TAG` trailer.
+    Standard,
+    /// A 128-byte `TAG` trailer preceded by a 128-byte ID3v1.2 `EXT` block.
+    Id3v12Ext,
+    /// A 128-byte `TAG` trailer preceded by a 227-byte enhanced `TAG+` block.
+    EnhancedTagPlus,
+}
+
+impl Layout {
+    /// Returns the total size in bytes occupied by this layout, counting both the trailer and
+    /// any preceding extension block.
+    pub fn size(self) -> u64 {
+        match self {
+            Layout::None => 0,
+            Layout::Standard => TAG_SIZE,
+            Layout::Id3v12Ext => TAG_SIZE + EXT_SIZE,
+            Layout::EnhancedTagPlus => TAG_SIZE + ENHANCED_SIZE,
+        }
+    }
+
+    /// Returns `true` if any ID3v1 data was detected.
+    pub fn is_present(self) -> bool {
+        !matches!(self, Layout::None)
+    }
+}
+
+/// The parsed contents of an ID3v1/ID3v1.1 tag, optionally widened by an ID3v1.2 "EXT" block or
+/// an enhanced "TAG+" block.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Tag {
+    /// Title.
+    pub title: Option<String>,
+    /// Artist.
+    pub artist: Option<String>,
+    /// Album.
+    pub album: Option<String>,
+    /// Year.
+    pub year: Option<String>,
+    /// Comment.
+    pub comment: Option<String>,
+    /// Track number, present in ID3v1.1.
+    pub track: Option<u8>,
+    /// Genre, translated from the numeric ID3v1 genre index when recognized.
+    pub genre: Option<String>,
+    /// Playback speed, from an enhanced "TAG+" block (1 = slow .. 4 = fast, 0 = unset).
+    pub speed: Option<u8>,
+    /// Start time of the track within the file (`mmm:ss`), from an enhanced "TAG+" block.
+    pub start_time: Option<String>,
+    /// End time of the track within the file (`mmm:ss`), from an enhanced "TAG+" block.
+    pub end_time: Option<String>,
+}
+
+fn latin1_trim(bytes: &[u8]) -> String {
+    let end = bytes
+        .iter()
+        .position(|&b| b == 0)
+        .unwrap_or(bytes.len());
+    bytes[..end]
+        .iter()
+        .map(|&b| b as char)
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
+
+fn non_empty(s: String) -> Option<String> {
+    if s.is_empty() {
+        None
+    } else {
+        Some(s)
+    }
+}
+
+/// Concatenates a short field with its extension, as found in a "TAG+" block.
+fn extend(short: Option<String>, extension: &[u8]) -> Option<String> {
+    let extension = latin1_trim(extension);
+    match (short, non_empty(extension)) {
+        (Some(short), Some(extension)) => Some(short + &extension),
+        (Some(short), None) => Some(short),
+        (None, extension) => extension,
+    }
+}
+
+impl Tag {
+    /// Determines which layout of ID3v1 tag, if any, is present at the end of the reader.
+    ///
+    /// This checks the file length before seeking, so it is safe to call on short files/streams.
+    pub fn is_candidate(mut reader: impl Read + Seek) -> Result<Layout> {
+        let len = reader.seek(SeekFrom::End(0))?;
+        if len < TAG_SIZE {
+            return Ok(Layout::None);
+        }
+
+        let mut tag_header = [0u8; 3];
+        reader.seek(SeekFrom::End(-(TAG_SIZE as i64)))?;
+        reader.read_exact(&mut tag_header)?;
+        if &tag_header != b"TAG" {
+            return Ok(Layout::None);
+        }
+
+        if len >= TAG_SIZE + ENHANCED_SIZE {
+            let mut header = [0u8; 4];
+            reader.seek(SeekFrom::End(-((TAG_SIZE + ENHANCED_SIZE) as i64)))?;
+            reader.read_exact(&mut header)?;
+            if &header == b"TAG+" {
+                return Ok(Layout::EnhancedTagPlus);
+            }
+        }
+
+        if len >= TAG_SIZE + EXT_SIZE {
+            let mut header = [0u8; 3];
+            reader.seek(SeekFrom::End(-((TAG_SIZE + EXT_SIZE) as i64)))?;
+            reader.read_exact(&mut header)?;
+            if &header == b"EXT" {
+                return Ok(Layout::Id3v12Ext);
+            }
+        }
+
+        Ok(Layout::Standard)
+    }
+
+    /// Attempts to read an ID3v1 tag, merging in an enhanced "TAG+" block or an ID3v1.2 "EXT"
+    /// block when present.
+    ///
+    /// If no ID3v1 tag is found, an error with [`ErrorKind::NoTag`] is returned.
+    pub fn read_from(mut reader: impl Read + Seek) -> Result<Tag> {
+        let layout = Self::is_candidate(&mut reader)?;
+        if layout == Layout::None {
+            return Err(Error::new(ErrorKind::NoTag, "No ID3v1 tag was found"));
+        }
+
+        reader.seek(SeekFrom::End(-(TAG_SIZE as i64)))?;
+        let mut block = [0u8; TAG_SIZE as usize];
+        reader.read_exact(&mut block)?;
+        assert_eq!(&block[0..3], b"TAG");
+
+        let mut tag = Tag {
+            title: non_empty(latin1_trim(&block[3..33])),
+            artist: non_empty(latin1_trim(&block[33..63])),
+            album: non_empty(latin1_trim(&block[63..93])),
+            year: non_empty(latin1_trim(&block[93..97])),
+            comment: non_empty(latin1_trim(&block[97..125])),
+            track: None,
+            genre: genre_name(block[127]).map(str::to_string),
+            speed: None,
+            start_time: None,
+            end_time: None,
+        };
+        // ID3v1.1: a zero byte before the track number indicates its presence.
+        if block[125] == 0 && block[126] != 0 {
+            tag.track = Some(block[126]);
+        } else {
+            tag.comment = non_empty(latin1_trim(&block[97..127]));
+        }
+
+        match layout {
+            Layout::EnhancedTagPlus => {
+                reader.seek(SeekFrom::End(-((TAG_SIZE + ENHANCED_SIZE) as i64)))?;
+                let mut enhanced = [0u8; ENHANCED_SIZE as usize];
+                reader.read_exact(&mut enhanced)?;
+                assert_eq!(&enhanced[0..4], b"TAG+");
+
+                tag.title = extend(tag.title, &enhanced[4..64]);
+                tag.artist = extend(tag.artist, &enhanced[64..124]);
+                tag.album = extend(tag.album, &enhanced[124..184]);
+                let speed = enhanced[184];
+                if speed != 0 {
+                    tag.speed = Some(speed);
+                }
+                if let Some(genre) = non_empty(latin1_trim(&enhanced[185..215])) {
+                    tag.genre = Some(genre);
+                }
+                tag.start_time = non_empty(latin1_trim(&enhanced[215..221]));
+                tag.end_time = non_empty(latin1_trim(&enhanced[221..227]));
+            }
+            Layout::Id3v12Ext => {
+                reader.seek(SeekFrom::End(-((TAG_SIZE + EXT_SIZE) as i64)))?;
+                let mut ext = [0u8; EXT_SIZE as usize];
+                reader.read_exact(&mut ext)?;
+                assert_eq!(&ext[0..3], b"EXT");
+
+                tag.title = extend(tag.title, &ext[3..35]);
+                tag.artist = extend(tag.artist, &ext[35..67]);
+                tag.album = extend(tag.album, &ext[67..99]);
+                tag.comment = extend(tag.comment, &ext[99..128]);
+            }
+            Layout::Standard | Layout::None => {}
+        }
+
+        Ok(tag)
+    }
+
+    /// Attempts to read an ID3v1 tag from the file at the indicated path.
+    pub fn read_from_path(path: impl AsRef<Path>) -> Result<Tag> {
+        Self::read_from(File::open(path)?)
+    }
+
+    /// Removes any ID3v1 tag (of any layout) from the file, returning the layout that was
+    /// removed.
+    pub fn remove_from_file(mut file: impl StorageFile) -> Result<Layout> {
+        let layout = Self::is_candidate(&mut file)?;
+        if layout.is_present() {
+            let len = file.seek(SeekFrom::End(0))?;
+            file.set_len(len - layout.size())?;
+        }
+        Ok(layout)
+    }
+
+    /// Removes any ID3v1 tag (of any layout) from the file at the indicated path, returning the
+    /// layout that was removed.
+    pub fn remove_from_path(path: impl AsRef<Path>) -> Result<Layout> {
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)?;
+        Self::remove_from_file(file)
+    }
+
+    /// Writes a plain 128-byte ID3v1.1 trailer to the file (or an ID3v1.0 trailer when the tag has
+    /// no track number), replacing any existing ID3v1 tag (of any layout).
+    pub fn write_to_file(&self, mut file: impl StorageFile) -> Result<()> {
+        Self::remove_from_file(&mut file)?;
+
+        let mut block = [0u8; TAG_SIZE as usize];
+        block[0..3].copy_from_slice(b"TAG");
+        write_field(&mut block[3..33], self.title.as_deref().unwrap_or(""));
+        write_field(&mut block[33..63], self.artist.as_deref().unwrap_or(""));
+        write_field(&mut block[63..93], self.album.as_deref().unwrap_or(""));
+        write_field(&mut block[93..97], self.year.as_deref().unwrap_or(""));
+        if let Some(track) = self.track {
+            write_field(&mut block[97..125], self.comment.as_deref().unwrap_or(""));
+            block[125] = 0;
+            block[126] = track;
+        } else {
+            write_field(&mut block[97..127], self.comment.as_deref().unwrap_or(""));
+        }
+        block[127] = self
+            .genre
+            .as_deref()
+            .and_then(genre_index)
+            .unwrap_or(0xff);
+
+        file.seek(SeekFrom::End(0))?;
+        file.write_all(&block)?;
+        Ok(())
+    }
+}
+
+/// Writes as much of `value` as fits into `field`, transcoding each character to its Latin-1
+/// byte (the inverse of the decode `latin1_trim` performs), substituting `?` for anything outside
+/// the Latin-1 range. Leaves the rest of `field` zero-padded.
+fn write_field(field: &mut [u8], value: &str) {
+    for (slot, ch) in field.iter_mut().zip(value.chars()) {
+        *slot = u8::try_from(ch as u32).unwrap_or(b'?');
+    }
+}
+
+impl From<Tag> for crate::Tag {
+    fn from(v1: Tag) -> Self {
+        use crate::TagLike;
+
+        let mut tag = crate::Tag::new();
+        if let Some(title) = v1.title {
+            tag.set_title(title);
+        }
+        if let Some(artist) = v1.artist {
+            tag.set_artist(artist);
+        }
+        if let Some(album) = v1.album {
+            tag.set_album(album);
+        }
+        if let Some(year) = v1.year.and_then(|y| y.parse::<i32>().ok()) {
+            tag.set_year(year);
+        }
+        if let Some(comment) = v1.comment {
+            tag.add_comment(crate::frame::Comment {
+                lang: "eng".to_string(),
+                description: String::new(),
+                text: comment,
+            });
+        }
+        if let Some(track) = v1.track {
+            tag.set_track(track as u32);
+        }
+        if let Some(genre) = v1.genre {
+            tag.set_genre(genre);
+        }
+        tag
+    }
+}
+
+impl From<&crate::Tag> for Tag {
+    /// Down-converts an ID3v2 tag into an ID3v1 tag, truncating the title, artist, album and
+    /// comment to their ID3v1 field widths. A genre found in the ID3v1 genre table is normalized
+    /// to the table's spelling, so that [`Tag::write_to_file`] can store its numeric index; other
+    /// genres are kept as they are. The year is not truncated here.
+    fn from(tag: &crate::Tag) -> Self {
+        use crate::TagLike;
+
+        let track = tag.track().map(|t| t as u8);
+        // ID3v1.1 (track present) narrows the comment field to 28 bytes to make room for the
+        // zero byte and track number that follow it; plain ID3v1.0 uses the full 30 bytes.
+        let comment_width = if track.is_some() { 28 } else { 30 };
+
+        Tag {
+            title: tag.title().map(|s| truncate(s, 30)),
+            artist: tag.artist().map(|s| truncate(s, 30)),
+            album: tag.album().map(|s| truncate(s, 30)),
+            year: tag.year().map(|y| y.to_string()),
+            comment: tag.comments().next().map(|c| truncate(&c.text, comment_width)),
+            track,
+            genre: tag
+                .genre()
+                .map(|g| genre_index(g).and_then(genre_name).unwrap_or(g).to_string()),
+            speed: None,
+            start_time: None,
+            end_time: None,
+        }
+    }
+}
+
+fn truncate(s: &str, max_chars: usize) -> String {
+    s.chars().take(max_chars).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn field(dest: &mut [u8], value: &[u8]) {
+        dest[..value.len()].copy_from_slice(value);
+    }
+
+    /// Builds a standard 128-byte `TAG` trailer.
+    fn standard_block(title: &str) -> Vec<u8> {
+        let mut block = vec![0u8; TAG_SIZE as usize];
+        field(&mut block, b"TAG");
+        field(&mut block[3..33], title.as_bytes());
+        field(&mut block[33..63], b"Artist");
+        field(&mut block[63..93], b"Album");
+        field(&mut block[93..97], b"2024");
+        field(&mut block[97..125], b"Comment");
+        block[127] = 31; // "Trance"
+        block
+    }
+
+    #[test]
+    fn test_is_candidate_standard() {
+        let block = standard_block("Title");
+        assert_eq!(Tag::is_candidate(Cursor::new(block)).unwrap(), Layout::Standard);
+    }
+
+    #[test]
+    fn test_is_candidate_none_when_too_short() {
+        let short = vec![0u8; TAG_SIZE as usize - 1];
+        assert_eq!(Tag::is_candidate(Cursor::new(short)).unwrap(), Layout::None);
+    }
+
+    #[test]
+    fn test_is_candidate_none_without_tag_marker() {
+        let block = vec![0u8; TAG_SIZE as usize];
+        assert_eq!(Tag::is_candidate(Cursor::new(block)).unwrap(), Layout::None);
+    }
+
+    #[test]
+    fn test_is_candidate_enhanced_tag_plus() {
+        let mut enhanced = vec![0u8; ENHANCED_SIZE as usize];
+        field(&mut enhanced, b"TAG+");
+        let mut file = enhanced;
+        file.extend(standard_block("Title"));
+        assert_eq!(
+            Tag::is_candidate(Cursor::new(file)).unwrap(),
+            Layout::EnhancedTagPlus
+        );
+    }
+
+    #[test]
+    fn test_is_candidate_ext() {
+        let mut file = vec![0u8; EXT_SIZE as usize];
+        field(&mut file, b"EXT");
+        file.extend(standard_block("Title"));
+        assert_eq!(Tag::is_candidate(Cursor::new(file)).unwrap(), Layout::Id3v12Ext);
+    }
+
+    #[test]
+    fn test_is_candidate_boundary_just_below_ext_size_falls_back_to_standard() {
+        // TAG_SIZE + EXT_SIZE - 1: one byte too short to even look for an "EXT" marker. This must
+        // not seek before the start of the stream trying to check for one.
+        let mut file = vec![0u8; EXT_SIZE as usize - 1];
+        file.extend(standard_block("Title"));
+        assert_eq!(file.len() as u64, TAG_SIZE + EXT_SIZE - 1);
+        assert_eq!(Tag::is_candidate(Cursor::new(file)).unwrap(), Layout::Standard);
+    }
+
+    #[test]
+    fn test_is_candidate_boundary_just_below_enhanced_size_falls_back_to_standard() {
+        // TAG_SIZE + ENHANCED_SIZE - 1: one byte too short to look for a "TAG+" marker, but still
+        // long enough to check for "EXT" (which isn't present here either).
+        let mut file = vec![0u8; ENHANCED_SIZE as usize - 1];
+        file.extend(standard_block("Title"));
+        assert_eq!(file.len() as u64, TAG_SIZE + ENHANCED_SIZE - 1);
+        assert_eq!(Tag::is_candidate(Cursor::new(file)).unwrap(), Layout::Standard);
+    }
+
+    #[test]
+    fn test_read_from_enhanced_tag_plus_extends_fields() {
+        let mut enhanced = vec![0u8; ENHANCED_SIZE as usize];
+        field(&mut enhanced, b"TAG+");
+        field(&mut enhanced[4..64], b" (Extended)");
+        field(&mut enhanced[64..124], b" Junior");
+        field(&mut enhanced[124..184], b" Deluxe");
+        enhanced[184] = 3; // speed
+        field(&mut enhanced[185..215], b"Power Metal");
+        field(&mut enhanced[215..221], b"000:30");
+        field(&mut enhanced[221..227], b"003:45");
+
+        let mut file = enhanced;
+        file.extend(standard_block("Title"));
+
+        let tag = Tag::read_from(Cursor::new(file)).unwrap();
+        assert_eq!(tag.title.as_deref(), Some("Title (Extended)"));
+        assert_eq!(tag.artist.as_deref(), Some("Artist Junior"));
+        assert_eq!(tag.album.as_deref(), Some("Album Deluxe"));
+        assert_eq!(tag.speed, Some(3));
+        assert_eq!(tag.genre.as_deref(), Some("Power Metal"));
+        assert_eq!(tag.start_time.as_deref(), Some("000:30"));
+        assert_eq!(tag.end_time.as_deref(), Some("003:45"));
+    }
+
+    #[test]
+    fn test_read_from_ext_extends_fields() {
+        let mut ext = vec![0u8; EXT_SIZE as usize];
+        field(&mut ext, b"EXT");
+        field(&mut ext[3..35], b" (Extended)");
+        field(&mut ext[35..67], b" Junior");
+        field(&mut ext[67..99], b" Deluxe");
+        field(&mut ext[99..128], b" - more comment");
+
+        let mut file = ext;
+        file.extend(standard_block("Title"));
+
+        let tag = Tag::read_from(Cursor::new(file)).unwrap();
+        assert_eq!(tag.title.as_deref(), Some("Title (Extended)"));
+        assert_eq!(tag.artist.as_deref(), Some("Artist Junior"));
+        assert_eq!(tag.album.as_deref(), Some("Album Deluxe"));
+        assert_eq!(tag.comment.as_deref(), Some("Comment - more comment"));
+    }
+
+    #[test]
+    fn test_read_from_standard_has_no_enhanced_fields() {
+        let tag = Tag::read_from(Cursor::new(standard_block("Title"))).unwrap();
+        assert_eq!(tag.title.as_deref(), Some("Title"));
+        assert_eq!(tag.speed, None);
+        assert_eq!(tag.start_time, None);
+        assert_eq!(tag.end_time, None);
+    }
+
+    #[test]
+    fn test_write_field_transcodes_latin1() {
+        let mut field = [0u8; 10];
+        write_field(&mut field, "Beyonc\u{e9}");
+        assert_eq!(&field, b"Beyonc\xe9\0\0\0");
+    }
+
+    #[test]
+    fn test_from_id3v2_comment_width_depends_on_track() {
+        use crate::TagLike;
+
+        let comment = "0123456789".repeat(4); // 40 chars, past both the 28- and 30-byte widths.
+
+        let mut no_track = crate::Tag::new();
+        no_track.add_comment(crate::frame::Comment {
+            lang: "eng".to_string(),
+            description: String::new(),
+            text: comment.clone(),
+        });
+        let v1_no_track = Tag::from(&no_track);
+        assert_eq!(v1_no_track.track, None);
+        assert_eq!(v1_no_track.comment.as_deref(), Some(&comment[..30]));
+
+        let mut with_track = crate::Tag::new();
+        with_track.set_track(5);
+        with_track.add_comment(crate::frame::Comment {
+            lang: "eng".to_string(),
+            description: String::new(),
+            text: comment.clone(),
+        });
+        let v1_with_track = Tag::from(&with_track);
+        assert_eq!(v1_with_track.track, Some(5));
+        assert_eq!(v1_with_track.comment.as_deref(), Some(&comment[..28]));
+    }
+}
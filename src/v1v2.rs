@@ -2,18 +2,23 @@ use crate::{v1, Error, ErrorKind, StorageFile, Tag, Version};
 use std::fs;
 use std::fs::File;
 use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Range;
 use std::path::Path;
 
+/// The 10-byte ID3v2 header/footer size, shared by the prepended header and the ID3v2.4 footer.
+const ID3V2_HEADER_SIZE: u64 = 10;
+/// The ID3v2.4 footer flag: the tag was written with a trailing mirror of the header.
+const FOOTER_FLAG: u8 = 0x10;
+
 /// Returns which tags are present in the specified file.
+///
+/// In addition to a prepended ID3v2 header, this also recognizes a trailing ID3v2.4 `3DI`
+/// footer, as used by streaming/broadcast sources where the tag is appended at the end.
 pub fn is_candidate(mut file: impl io::Read + io::Seek) -> crate::Result<FormatVersion> {
-    let v2 = Tag::is_candidate(&mut file)?;
+    let v2 = Tag::is_candidate(&mut file)? || trailing_footer_size(&mut file)?.is_some();
     let v1 = v1::Tag::is_candidate(&mut file)?;
-    Ok(match (v1, v2) {
-        (false, false) => FormatVersion::None,
-        (true, false) => FormatVersion::Id3v1,
-        (false, true) => FormatVersion::Id3v2,
-        (true, true) => FormatVersion::Both,
-    })
+    Ok(format_version(v1, v2))
 }
 
 /// Returns which tags are present in the specified file.
@@ -21,11 +26,31 @@ pub fn is_candidate_path(path: impl AsRef<Path>) -> crate::Result<FormatVersion>
     is_candidate(File::open(path)?)
 }
 
+/// Attempts to read an ID3v2 tag, falling back to a trailing ID3v2.4 `3DI` footer (as used by
+/// streaming/broadcast sources) when no header is found at the start of the file.
+fn read_id3v2(mut file: impl io::Read + io::Seek) -> crate::Result<Tag> {
+    match Tag::read_from2(&mut file) {
+        Err(Error {
+            kind: ErrorKind::NoTag,
+            ..
+        }) => {}
+        result => return result,
+    }
+
+    match id3v2_header_start_from_footer(&mut file)? {
+        Some(start) => {
+            file.seek(SeekFrom::Start(start))?;
+            Tag::read_from2(&mut file)
+        }
+        None => Err(Error::new(ErrorKind::NoTag, "No ID3v2 tag was found")),
+    }
+}
+
 /// Attempts to read an ID3v2 or ID3v1 tag, in that order.
 ///
 /// If neither version tag is found, an error with [`ErrorKind::NoTag`] is returned.
 pub fn read_from(mut file: impl io::Read + io::Seek) -> crate::Result<Tag> {
-    match Tag::read_from2(&mut file) {
+    match read_id3v2(&mut file) {
         Err(Error {
             kind: ErrorKind::NoTag,
             ..
@@ -56,21 +81,175 @@ pub fn read_from_path(path: impl AsRef<Path>) -> crate::Result<Tag> {
     read_from(File::open(path)?)
 }
 
-/// Writes the specified tag to a file. Any existing ID3v2 tag is replaced or added if it is not
-/// present.
+/// Like [`read_from`], but when both an ID3v2 and an ID3v1 tag are present, fills any of the
+/// title, artist, album, year, comment, track and genre fields that are absent from the ID3v2
+/// tag with the corresponding ID3v1 value.
 ///
-/// If any ID3v1 tag is present it will be REMOVED as it is not able to fully represent a ID3v2
-/// tag.
-pub fn write_to_file(mut file: impl StorageFile, tag: &Tag, version: Version) -> crate::Result<()> {
+/// This is useful for recovering legacy metadata that a truncated or sparse ID3v2 tag is
+/// missing. The ID3v2 tag always takes precedence: ID3v1 only supplies values for fields that
+/// don't already exist.
+pub fn read_merged(mut file: impl io::Read + io::Seek) -> crate::Result<Tag> {
+    use crate::TagLike;
+
+    let v2 = match read_id3v2(&mut file) {
+        Err(Error {
+            kind: ErrorKind::NoTag,
+            ..
+        }) => None,
+        Err(err) => return Err(err),
+        Ok(tag) => Some(tag),
+    };
+
+    let v1 = match v1::Tag::read_from(&mut file) {
+        Err(Error {
+            kind: ErrorKind::NoTag,
+            ..
+        }) => None,
+        Err(err) => return Err(err),
+        Ok(tag) => Some(tag),
+    };
+
+    match (v2, v1) {
+        (Some(mut v2), Some(v1)) => {
+            if v2.title().is_none() {
+                if let Some(title) = v1.title {
+                    v2.set_title(title);
+                }
+            }
+            if v2.artist().is_none() {
+                if let Some(artist) = v1.artist {
+                    v2.set_artist(artist);
+                }
+            }
+            if v2.album().is_none() {
+                if let Some(album) = v1.album {
+                    v2.set_album(album);
+                }
+            }
+            if v2.year().is_none() {
+                if let Some(year) = v1.year.and_then(|y| y.parse::<i32>().ok()) {
+                    v2.set_year(year);
+                }
+            }
+            if v2.track().is_none() {
+                if let Some(track) = v1.track {
+                    v2.set_track(track as u32);
+                }
+            }
+            if v2.genre().is_none() {
+                if let Some(genre) = v1.genre {
+                    v2.set_genre(genre);
+                }
+            }
+            if v2.comments().next().is_none() {
+                if let Some(comment) = v1.comment {
+                    v2.add_comment(crate::frame::Comment {
+                        lang: "eng".to_string(),
+                        description: String::new(),
+                        text: comment,
+                    });
+                }
+            }
+            Ok(v2)
+        }
+        (Some(v2), None) => Ok(v2),
+        (None, Some(v1)) => Ok(v1.into()),
+        (None, None) => Err(Error::new(
+            ErrorKind::NoTag,
+            "Neither a ID3v2 or ID3v1 tag was found",
+        )),
+    }
+}
+
+/// Convenience function for [`read_merged`].
+pub fn read_merged_path(path: impl AsRef<Path>) -> crate::Result<Tag> {
+    read_merged(File::open(path)?)
+}
+
+/// Writes the specified tag to a file. Any existing ID3v2 tag is replaced or added if it is not
+/// present. `policy` controls what happens to any existing ID3v1 tag, see [`V1Policy`].
+/// `footer` requests a trailing 10-byte `3DI` footer mirroring the header, as used by
+/// streaming/broadcast sources; it is only honored for [`Version::Id3v24`], which is the only
+/// version that defines a footer.
+pub fn write_to_file(
+    mut file: impl StorageFile,
+    tag: &Tag,
+    version: Version,
+    policy: V1Policy,
+    footer: bool,
+) -> crate::Result<()> {
     tag.write_to_file(&mut file, version)?;
-    v1::Tag::remove_from_file(&mut file)?;
+    if footer && version == Version::Id3v24 {
+        write_id3v24_footer(&mut file)?;
+    }
+    match policy {
+        V1Policy::Remove => {
+            v1::Tag::remove_from_file(&mut file)?;
+        }
+        V1Policy::Keep => {}
+        V1Policy::Synchronize => {
+            v1::Tag::from(tag).write_to_file(&mut file)?;
+        }
+    }
     Ok(())
 }
 
 /// Conventience function for [`write_to_file`].
-pub fn write_to_path(path: impl AsRef<Path>, tag: &Tag, version: Version) -> crate::Result<()> {
+pub fn write_to_path(
+    path: impl AsRef<Path>,
+    tag: &Tag,
+    version: Version,
+    policy: V1Policy,
+    footer: bool,
+) -> crate::Result<()> {
     let file = fs::OpenOptions::new().read(true).write(true).open(path)?;
-    write_to_file(file, tag, version)
+    write_to_file(file, tag, version, policy, footer)
+}
+
+/// Inserts a 10-byte `3DI` footer, mirroring the version/flags/size of the ID3v2 header that was
+/// just written at the start of the file, immediately after the tag's frame data.
+fn write_id3v24_footer(mut file: impl StorageFile) -> crate::Result<()> {
+    let mut header = [0u8; ID3V2_HEADER_SIZE as usize];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut header)?;
+    if &header[0..3] != b"ID3" {
+        return Ok(());
+    }
+
+    // Readers only know to expect a footer if the header says so: flip the footer-present flag
+    // on the on-disk header to match, so `id3v2_range` (and any spec-compliant decoder) includes
+    // the footer's 10 bytes in the tag.
+    header[5] |= FOOTER_FLAG;
+    file.seek(SeekFrom::Start(5))?;
+    file.write_all(&header[5..6])?;
+
+    let tag_size = synchsafe_decode(&header[6..10]);
+    let insert_at = ID3V2_HEADER_SIZE + tag_size;
+    let mut footer = header;
+    footer[0..3].copy_from_slice(b"3DI");
+
+    let len = file.seek(SeekFrom::End(0))?;
+    let mut tail = Vec::with_capacity((len - insert_at) as usize);
+    file.seek(SeekFrom::Start(insert_at))?;
+    file.read_to_end(&mut tail)?;
+    file.seek(SeekFrom::Start(insert_at))?;
+    file.write_all(&footer)?;
+    file.write_all(&tail)?;
+    Ok(())
+}
+
+/// Controls what happens to any existing (or synthesizable) ID3v1 tag when writing an ID3v2 tag
+/// via [`write_to_file`]/[`write_to_path`].
+#[derive(Copy, Clone, Debug, Hash, Ord, PartialOrd, Eq, PartialEq)]
+pub enum V1Policy {
+    /// Remove any existing ID3v1 tag. This is lossy for players that only read ID3v1, but
+    /// matches the historical behaviour of this crate.
+    Remove,
+    /// Leave any existing ID3v1 tag untouched.
+    Keep,
+    /// Down-convert the ID3v2 tag into a fresh ID3v1 tag and write both, replacing any existing
+    /// ID3v1 tag (of any layout) with a plain 128-byte trailer.
+    Synchronize,
 }
 
 /// Ensures that both ID3v1 and ID3v2 are not present in the specified file.
@@ -79,12 +258,7 @@ pub fn write_to_path(path: impl AsRef<Path>, tag: &Tag, version: Version) -> cra
 pub fn remove_from_path(path: impl AsRef<Path>) -> crate::Result<FormatVersion> {
     let v2 = Tag::remove_from_path(&path)?;
     let v1 = v1::Tag::remove_from_path(path)?;
-    Ok(match (v1, v2) {
-        (false, false) => FormatVersion::None,
-        (true, false) => FormatVersion::Id3v1,
-        (false, true) => FormatVersion::Id3v2,
-        (true, true) => FormatVersion::Both,
-    })
+    Ok(format_version(v1, v2))
 }
 
 /// An enum that represents the precense state of both tag format versions.
@@ -92,12 +266,129 @@ pub fn remove_from_path(path: impl AsRef<Path>) -> crate::Result<FormatVersion>
 pub enum FormatVersion {
     /// No tags.
     None,
-    /// ID3v1
-    Id3v1,
+    /// ID3v1, carrying the on-disk layout of the tag (plain, or extended by an "EXT"/"TAG+"
+    /// block).
+    Id3v1(v1::Layout),
     /// ID3v2
     Id3v2,
     /// ID3v1 + ID3v2
-    Both,
+    Both(v1::Layout),
+}
+
+/// Combines the ID3v1 layout and ID3v2 presence bits produced by the various scanning functions
+/// into a single [`FormatVersion`].
+fn format_version(v1: v1::Layout, v2: bool) -> FormatVersion {
+    match (v1, v2) {
+        (v1::Layout::None, false) => FormatVersion::None,
+        (layout, false) => FormatVersion::Id3v1(layout),
+        (v1::Layout::None, true) => FormatVersion::Id3v2,
+        (layout, true) => FormatVersion::Both(layout),
+    }
+}
+
+/// The byte ranges occupied by each tag region in a file, as reported by [`locate`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TagLocation {
+    /// Which tag formats were found.
+    pub format: FormatVersion,
+    /// Byte range of the ID3v2 header and frames, including the trailing 10-byte footer for
+    /// ID3v2.4 tags that were written with one.
+    pub id3v2: Option<Range<u64>>,
+    /// Byte range of the ID3v1 tag, including any preceding ID3v1.2 "EXT" or enhanced "TAG+"
+    /// block.
+    pub id3v1: Option<Range<u64>>,
+}
+
+/// Scans the file for ID3v2 and ID3v1 tags without parsing any frame contents, reporting the
+/// byte range each one occupies. This allows a tool to strip or replace one tag in place without
+/// rewriting the rest of the file.
+pub fn locate(mut file: impl io::Read + io::Seek) -> crate::Result<TagLocation> {
+    let id3v2 = id3v2_range(&mut file)?;
+    let v1_layout = v1::Tag::is_candidate(&mut file)?;
+    let id3v1 = if v1_layout.is_present() {
+        let len = file.seek(SeekFrom::End(0))?;
+        Some((len - v1_layout.size())..len)
+    } else {
+        None
+    };
+
+    Ok(TagLocation {
+        format: format_version(v1_layout, id3v2.is_some()),
+        id3v2,
+        id3v1,
+    })
+}
+
+/// Convenience function for [`locate`].
+pub fn locate_path(path: impl AsRef<Path>) -> crate::Result<TagLocation> {
+    locate(File::open(path)?)
+}
+
+/// Determines the byte range of the ID3v2 tag, reading only the 10-byte header rather than the
+/// frames themselves; for ID3v2.4 tags whose header sets the footer flag, the footer's 10 bytes
+/// are added to the range without being read. Falls back to reading a trailing `3DI` footer when
+/// no header is found at the start of the file.
+fn id3v2_range(mut file: impl io::Read + io::Seek) -> crate::Result<Option<Range<u64>>> {
+    let mut header = [0u8; ID3V2_HEADER_SIZE as usize];
+    file.seek(SeekFrom::Start(0))?;
+    if file.read_exact(&mut header).is_ok() && &header[0..3] == b"ID3" {
+        let flags = header[5];
+        let tag_size = synchsafe_decode(&header[6..10]);
+        let mut end = ID3V2_HEADER_SIZE + tag_size;
+        if header[3] == 4 && flags & FOOTER_FLAG != 0 {
+            end += ID3V2_HEADER_SIZE;
+        }
+        return Ok(Some(0..end));
+    }
+
+    if let Some(tag_size) = trailing_footer_size(&mut file)? {
+        let len = file.seek(SeekFrom::End(0))?;
+        let total = ID3V2_HEADER_SIZE + tag_size + ID3V2_HEADER_SIZE;
+        if total <= len {
+            return Ok(Some((len - total)..len));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Reads the trailing 10 bytes of the file and returns the tag size encoded in a `3DI` footer,
+/// if one is present.
+fn trailing_footer_size(mut file: impl io::Read + io::Seek) -> crate::Result<Option<u64>> {
+    let len = file.seek(SeekFrom::End(0))?;
+    if len < ID3V2_HEADER_SIZE {
+        return Ok(None);
+    }
+
+    let mut footer = [0u8; ID3V2_HEADER_SIZE as usize];
+    file.seek(SeekFrom::End(-(ID3V2_HEADER_SIZE as i64)))?;
+    file.read_exact(&mut footer)?;
+    if &footer[0..3] != b"3DI" {
+        return Ok(None);
+    }
+    Ok(Some(synchsafe_decode(&footer[6..10])))
+}
+
+/// Computes the offset of the ID3v2 header that a trailing `3DI` footer mirrors, by reading the
+/// tag size out of the footer.
+fn id3v2_header_start_from_footer(mut file: impl io::Read + io::Seek) -> crate::Result<Option<u64>> {
+    let tag_size = match trailing_footer_size(&mut file)? {
+        Some(tag_size) => tag_size,
+        None => return Ok(None),
+    };
+    let len = file.seek(SeekFrom::End(0))?;
+    let total = ID3V2_HEADER_SIZE + tag_size + ID3V2_HEADER_SIZE;
+    if total > len {
+        return Ok(None);
+    }
+    Ok(Some(len - total))
+}
+
+/// Decodes a 4-byte ID3v2 synchsafe integer (7 significant bits per byte).
+fn synchsafe_decode(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(0u64, |acc, &b| (acc << 7) | (b & 0x7f) as u64)
 }
 
 #[cfg(test)]
@@ -122,14 +413,17 @@ mod tests {
     #[test]
     fn test_is_candidate() {
         let tmp = file_with_both_formats();
-        assert_eq!(is_candidate_path(&tmp).unwrap(), FormatVersion::Both);
+        assert_eq!(
+            is_candidate_path(&tmp).unwrap(),
+            FormatVersion::Both(v1::Layout::Standard)
+        );
         assert_eq!(
             is_candidate_path("testdata/image.jpg").unwrap(),
             FormatVersion::None
         );
         assert_eq!(
             is_candidate_path("testdata/id3v1.id3").unwrap(),
-            FormatVersion::Id3v1
+            FormatVersion::Id3v1(v1::Layout::Standard)
         );
         assert_eq!(
             is_candidate_path("testdata/id3v24.id3").unwrap(),
@@ -148,21 +442,143 @@ mod tests {
         assert_eq!(v1.genre(), Some("Trance"));
     }
 
+    #[test]
+    fn test_read_merged_path() {
+        let tmp = file_with_both_formats();
+        let v1 = v1::Tag::read_from_path("testdata/id3v1.id3").unwrap();
+
+        // The ID3v2 tag wins where it has a value...
+        let merged = read_merged_path(&tmp).unwrap();
+        assert_eq!(merged.genre(), Some("Genre"));
+
+        // ...but ID3v1 fills in anything the ID3v2 tag is missing, such as its track number.
+        assert_eq!(merged.track(), v1.track.map(|t| t as u32));
+    }
+
     #[test]
     fn test_write_to_path() {
         let tmp = file_with_both_formats();
 
         let mut tag = read_from_path(&tmp).unwrap();
         tag.set_artist("High Contrast");
-        write_to_path(&tmp, &tag, Version::Id3v24).unwrap();
+        write_to_path(&tmp, &tag, Version::Id3v24, V1Policy::Remove, false).unwrap();
 
         assert_eq!(is_candidate_path(&tmp).unwrap(), FormatVersion::Id3v2);
     }
 
+    #[test]
+    fn test_write_to_path_keep() {
+        let tmp = file_with_both_formats();
+        let original_v1 = v1::Tag::read_from_path(&tmp).unwrap();
+
+        let mut tag = read_from_path(&tmp).unwrap();
+        tag.set_artist("High Contrast");
+        write_to_path(&tmp, &tag, Version::Id3v24, V1Policy::Keep, false).unwrap();
+
+        // The pre-existing ID3v1 trailer must survive the ID3v2 rewrite, layout and contents
+        // unchanged.
+        assert_eq!(
+            is_candidate_path(&tmp).unwrap(),
+            FormatVersion::Both(v1::Layout::Standard)
+        );
+        assert_eq!(v1::Tag::read_from_path(&tmp).unwrap(), original_v1);
+    }
+
+    #[test]
+    fn test_write_to_path_synchronize() {
+        let tmp = file_with_both_formats();
+
+        let mut tag = read_from_path(&tmp).unwrap();
+        tag.set_artist("High Contrast");
+        write_to_path(&tmp, &tag, Version::Id3v24, V1Policy::Synchronize, false).unwrap();
+
+        assert_eq!(
+            is_candidate_path(&tmp).unwrap(),
+            FormatVersion::Both(v1::Layout::Standard)
+        );
+        let v1 = v1::Tag::read_from_path(&tmp).unwrap();
+        assert_eq!(v1.artist.as_deref(), Some("High Contrast"));
+    }
+
     #[test]
     fn test_remove_from_path() {
         let tmp = file_with_both_formats();
 
-        assert_eq!(remove_from_path(&tmp).unwrap(), FormatVersion::Both);
+        assert_eq!(
+            remove_from_path(&tmp).unwrap(),
+            FormatVersion::Both(v1::Layout::Standard)
+        );
+    }
+
+    #[test]
+    fn test_locate_path() {
+        let tmp = file_with_both_formats();
+
+        let location = locate_path(&tmp).unwrap();
+        assert_eq!(location.format, FormatVersion::Both(v1::Layout::Standard));
+
+        let id3v2 = location.id3v2.unwrap();
+        assert_eq!(id3v2.start, 0);
+
+        let id3v1 = location.id3v1.unwrap();
+        let len = fs::metadata(&tmp).unwrap().len();
+        assert_eq!(id3v1, (len - 128)..len);
+    }
+
+    #[test]
+    fn test_trailing_footer_is_detected() {
+        // A stream-style file carrying only a trailing ID3v2.4 "3DI" footer (no prepended
+        // header) should still be recognized as an ID3v2 candidate.
+        let mut tag_bytes = fs::read("testdata/id3v24.id3").unwrap();
+        let header = tag_bytes[0..10].to_vec();
+        let mut footer = header;
+        footer[0..3].copy_from_slice(b"3DI");
+        tag_bytes.drain(0..10); // Drop the prepended header; only the footer marks the tag.
+        tag_bytes.extend_from_slice(&footer);
+
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        tmp.write_all(&tag_bytes).unwrap();
+
+        assert_eq!(
+            is_candidate_path(&tmp).unwrap(),
+            FormatVersion::Id3v2
+        );
+    }
+
+    #[test]
+    fn test_write_to_path_with_footer() {
+        let tmp = file_with_both_formats();
+
+        let mut tag = read_from_path(&tmp).unwrap();
+        tag.set_artist("High Contrast");
+        write_to_path(&tmp, &tag, Version::Id3v24, V1Policy::Keep, true).unwrap();
+
+        // The header must advertise the footer it was written with, or a spec-compliant decoder
+        // won't know to look for it.
+        let mut header = [0u8; 10];
+        File::open(&tmp)
+            .unwrap()
+            .read_exact(&mut header)
+            .unwrap();
+        assert_ne!(header[5] & FOOTER_FLAG, 0);
+
+        let location = locate_path(&tmp).unwrap();
+        let id3v2 = location.id3v2.unwrap();
+        let len = fs::metadata(&tmp).unwrap().len();
+
+        // The footer's trailing 10 bytes must be included in the reported tag range, and a
+        // `3DI` magic must actually be sitting at the end of that range.
+        let mut footer_magic = [0u8; 3];
+        let mut reader = File::open(&tmp).unwrap();
+        reader.seek(SeekFrom::Start(id3v2.end - 10)).unwrap();
+        reader.read_exact(&mut footer_magic).unwrap();
+        assert_eq!(&footer_magic, b"3DI");
+        assert!(id3v2.end < len, "footer must precede the ID3v1 trailer");
+
+        // `V1Policy::Keep` means the pre-existing ID3v1 trailer should still be there too.
+        assert_eq!(
+            is_candidate_path(&tmp).unwrap(),
+            FormatVersion::Both(v1::Layout::Standard)
+        );
     }
 }